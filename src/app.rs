@@ -1,20 +1,20 @@
-use std::{collections::HashMap, io::Cursor};
+use std::{collections::HashMap, io::{Cursor, Write}};
 
 use base64::{Engine as _, engine::general_purpose};
 use gloo_console::{info, error};
 use gloo_file::{File, callbacks::FileReader};
-use image::{EncodableLayout, GenericImageView, ImageFormat, DynamicImage};
+use image::{AnimationDecoder, EncodableLayout, Frame, GenericImageView, ImageFormat, DynamicImage};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use serde::{Deserialize, Serialize};
 
-use web_sys::{DragEvent, Event, FileList, HtmlInputElement};
-use wasm_bindgen::prelude::*;
+use web_sys::{Blob, BlobPropertyBag, DragEvent, Event, FileList, HtmlAnchorElement, HtmlInputElement, Url};
+use wasm_bindgen::JsCast;
 use yew::prelude::*;
 use yew_bootstrap::{util::{include_cdn, include_cdn_js, Color}, component::Spinner};
 
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "tauri"])]
-    async fn invoke(cmd: &str, args: JsValue) -> JsValue;
-}
+#[path = "pipeline.rs"]
+mod pipeline;
+use pipeline::{build_background, with_output_extension, FillMode, OutputFormat};
 
 #[derive(Clone)]
 struct FileDetails {
@@ -23,6 +23,30 @@ struct FileDetails {
     data: Vec<u8>,
 }
 
+#[derive(Serialize)]
+struct BackendFileRequest {
+    name: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct BackendConvertRequest {
+    tile: Option<String>,
+    files: Vec<BackendFileRequest>,
+    strip_metadata: bool,
+    format: String,
+    quality: Option<u8>,
+    fill_mode: String,
+    fill_color: [u8; 3],
+}
+
+#[derive(Deserialize)]
+struct BackendConvertedFile {
+    name: String,
+    mime_type: String,
+    data: String,
+}
+
 pub enum FileType {
     Tile,
     File,
@@ -34,6 +58,11 @@ pub enum Msg {
     Files(Vec<File>),
     Tile(Option<File>),
     ConvertedFiles(Vec<File>),
+    ToggleStripMetadata(bool),
+    SetOutputFormat(OutputFormat),
+    SetFillMode(FillMode),
+    SetFillColor([u8; 3]),
+    DownloadAll,
     NoOp,
 }
 
@@ -41,6 +70,10 @@ pub enum Msg {
 pub struct Props {
     converting: bool,
     loading: bool,
+    strip_metadata: bool,
+    output_format: OutputFormat,
+    fill_mode: FillMode,
+    fill_color: [u8; 3],
 }
 
 pub struct App {
@@ -64,6 +97,10 @@ impl Component for App {
             props: Props {
                 converting: false,
                 loading: true,
+                strip_metadata: true,
+                output_format: OutputFormat::Png,
+                fill_mode: FillMode::Tile,
+                fill_color: [255, 255, 255],
             },
         }
     }
@@ -162,6 +199,28 @@ impl Component for App {
                 }
                 true
             },
+            Msg::ToggleStripMetadata(enabled) => {
+                self.props.strip_metadata = enabled;
+                true
+            }
+            Msg::SetOutputFormat(format) => {
+                self.props.output_format = format;
+                true
+            }
+            Msg::SetFillMode(fill_mode) => {
+                self.props.fill_mode = fill_mode;
+                true
+            }
+            Msg::SetFillColor(fill_color) => {
+                self.props.fill_color = fill_color;
+                true
+            }
+            Msg::DownloadAll => {
+                if let Err(e) = Self::download_all(&self.new_files) {
+                    error!(format!("Error downloading all: {}", e));
+                }
+                false
+            }
             Msg::NoOp => false,
         }
     }
@@ -172,38 +231,129 @@ impl Component for App {
             <div id="wrapper">
                 {include_cdn()}
                 <p id="title">{"Convert your pictures"}</p>
-                <div id="upload-boxes">
-                    <label for="tile-upload">
-                        <div class="my-container"
-                            ondrop={ctx.link().callback(|event: DragEvent| {
-                                event.prevent_default();
-                                let files = event.data_transfer().unwrap().files();
-                                Self::upload_tile(files)
+                <div id="options">
+                    <label for="strip-metadata">
+                        <input
+                            id="strip-metadata"
+                            type="checkbox"
+                            checked={self.props.strip_metadata}
+                            onchange={ctx.link().callback(|e: Event| {
+                                let input: HtmlInputElement = e.target_unchecked_into();
+                                Msg::ToggleStripMetadata(input.checked())
                             })}
-                            ondragover={Callback::from(|event: DragEvent| {
-                                event.prevent_default();
+                        />
+                        {"Remove metadata (EXIF/location/camera info)"}
+                    </label>
+                    <div>
+                        <label for="output-format">{"Output format"}</label>
+                        <select
+                            id="output-format"
+                            onchange={ctx.link().callback(|e: Event| {
+                                let input: HtmlInputElement = e.target_unchecked_into();
+                                match input.value().as_str() {
+                                    "jpeg" => Msg::SetOutputFormat(OutputFormat::Jpeg(85)),
+                                    "webp" => Msg::SetOutputFormat(OutputFormat::WebP),
+                                    "gif" => Msg::SetOutputFormat(OutputFormat::Gif),
+                                    _ => Msg::SetOutputFormat(OutputFormat::Png),
+                                }
                             })}
-                            ondragenter={Callback::from(|event: DragEvent| {
-                                event.prevent_default();
+                        >
+                            <option value="png" selected={self.props.output_format.select_value() == "png"}>{"PNG"}</option>
+                            <option value="jpeg" selected={self.props.output_format.select_value() == "jpeg"}>{"JPEG"}</option>
+                            <option value="webp" selected={self.props.output_format.select_value() == "webp"}>{"WebP"}</option>
+                            <option value="gif" selected={self.props.output_format.select_value() == "gif"}>{"GIF"}</option>
+                        </select>
+                        if let OutputFormat::Jpeg(quality) = self.props.output_format {
+                            <label for="jpeg-quality">{format!("Quality: {}", quality)}</label>
+                            <input
+                                id="jpeg-quality"
+                                type="range"
+                                min="1"
+                                max="100"
+                                value={quality.to_string()}
+                                onchange={ctx.link().callback(|e: Event| {
+                                    let input: HtmlInputElement = e.target_unchecked_into();
+                                    let quality = input.value().parse().unwrap_or(85);
+                                    Msg::SetOutputFormat(OutputFormat::Jpeg(quality))
+                                })}
+                            />
+                        }
+                    </div>
+                    <div>
+                        <label for="fill-mode">{"Background fill"}</label>
+                        <select
+                            id="fill-mode"
+                            onchange={ctx.link().callback(|e: Event| {
+                                let input: HtmlInputElement = e.target_unchecked_into();
+                                match input.value().as_str() {
+                                    "solid" => Msg::SetFillMode(FillMode::Solid),
+                                    "blur" => Msg::SetFillMode(FillMode::Blur),
+                                    _ => Msg::SetFillMode(FillMode::Tile),
+                                }
                             })}
                         >
-                            <h4>{"Upload Tile Images"}</h4>
-                            <p>{"Drag and drop file here"}<br/>
-                            {"or"}<br/>
-                            {"Click to select file"}</p>
-                        </div>
-                    </label>
+                            <option value="tile" selected={self.props.fill_mode.select_value() == "tile"}>{"Tile image"}</option>
+                            <option value="solid" selected={self.props.fill_mode.select_value() == "solid"}>{"Solid color"}</option>
+                            <option value="blur" selected={self.props.fill_mode.select_value() == "blur"}>{"Blurred backdrop"}</option>
+                        </select>
+                        if self.props.fill_mode == FillMode::Solid {
+                            <input
+                                id="fill-color"
+                                type="color"
+                                value={format!("#{:02x}{:02x}{:02x}", self.props.fill_color[0], self.props.fill_color[1], self.props.fill_color[2])}
+                                onchange={ctx.link().callback(|e: Event| {
+                                    let input: HtmlInputElement = e.target_unchecked_into();
+                                    Msg::SetFillColor(Self::parse_hex_color(&input.value()))
+                                })}
+                            />
+                        }
+                    </div>
+                </div>
+                <div id="upload-boxes">
+                    if self.props.fill_mode == FillMode::Tile {
+                        <label for="tile-upload">
+                            <div class="my-container"
+                                ondrop={ctx.link().callback(|event: DragEvent| {
+                                    event.prevent_default();
+                                    let files = event.data_transfer().unwrap().files();
+                                    Self::upload_tile(files)
+                                })}
+                                ondragover={Callback::from(|event: DragEvent| {
+                                    event.prevent_default();
+                                })}
+                                ondragenter={Callback::from(|event: DragEvent| {
+                                    event.prevent_default();
+                                })}
+                            >
+                                <h4>{"Upload Tile Images"}</h4>
+                                <p>{"Drag and drop file here"}<br/>
+                                {"or"}<br/>
+                                {"Click to select file"}</p>
+                            </div>
+                        </label>
+                    }
                     <div
                         class="my-container"
                         onclick={
-                          if self.props.loading || self.props.converting {
+                          if self.props.loading || self.props.converting || (self.props.fill_mode == FillMode::Tile && self.tile.is_none()) {
                             Callback::noop()
                           } else {
                             let files = self.files.clone();
                             let tile = self.tile.clone();
                             let props = ctx.props().clone();
-                            ctx.link().callback(move |_| {
-                                Self::convert_files(files.clone(), tile.clone(), props.converting)
+                            let strip_metadata = self.props.strip_metadata;
+                            let output_format = self.props.output_format;
+                            let fill_mode = self.props.fill_mode;
+                            let fill_color = self.props.fill_color;
+                            let link = ctx.link().clone();
+                            Callback::from(move |_| {
+                                let files = files.clone();
+                                let tile = tile.clone();
+                                let link = link.clone();
+                                wasm_bindgen_futures::spawn_local(async move {
+                                    let msg = Self::convert_files(files, tile, props.converting, strip_metadata, output_format, fill_mode, fill_color).await;
+                                    link.send_message(msg);
+                                });
                             })
                           }
                         }
@@ -251,6 +401,11 @@ impl Component for App {
                         Self::upload_files(input.files())
                     })}
                 />
+                if !self.new_files.is_empty() {
+                    <div id="download-all" class="my-container" onclick={ctx.link().callback(|_| Msg::DownloadAll)}>
+                        <p>{"Download all"}</p>
+                    </div>
+                }
                 <div id="preview-area">
                     if let Some(tile) = &self.tile {
                         { Self::view_file(&tile, FileType::Tile) }
@@ -325,59 +480,283 @@ impl App {
         }
     }
 
-    fn convert_files(files: Vec<FileDetails>, tile: Option<FileDetails>, converting: bool) -> Msg {
+    async fn convert_files(files: Vec<FileDetails>, tile: Option<FileDetails>, converting: bool, strip_metadata: bool, output_format: OutputFormat, fill_mode: FillMode, fill_color: [u8; 3]) -> Msg {
         if converting {
             return Msg::NoOp;
         }
+        if fill_mode == FillMode::Tile && tile.is_none() {
+            error!("Tile fill mode selected but no tile image was uploaded");
+            return Msg::NoOp;
+        }
         info!("Convert");
-        let mut result = Vec::new();
-        if let Some(tile) = tile {
-            info!("Loading tile");
-            let tile = image::load_from_memory(&tile.data);
-            match tile {
-                Ok(tile) => {
-                    info!("Tile loaded");
-                    let tile = tile.resize(256, 256, image::imageops::FilterType::Nearest);
-                    let tile = tile.to_rgba8();
-                    let tile = image::DynamicImage::ImageRgba8(tile);
-                    for file in files {
-                      result.push(Self::convert(file, tile.clone()));
+
+        if let Some(msg) = Self::convert_via_backend(&files, tile.as_ref(), strip_metadata, output_format, fill_mode, fill_color).await {
+            return msg;
+        }
+
+        info!("No native backend available, converting in-browser");
+        let loaded_tile = match &tile {
+            Some(tile) => {
+                info!("Loading tile");
+                match image::load_from_memory(&tile.data) {
+                    Ok(loaded_tile) => {
+                        info!("Tile loaded");
+                        let loaded_tile = loaded_tile.resize(256, 256, image::imageops::FilterType::Nearest);
+                        Some(image::DynamicImage::ImageRgba8(loaded_tile.to_rgba8()))
+                    }
+                    Err(e) => {
+                        error!(format!("Error loading tile: {}", e));
+                        return Msg::NoOp;
                     }
-                    Msg::ConvertedFiles(result)
-                }
-                Err(e) => {
-                    error!(format!("Error loading tile: {}", e));
-                    Msg::NoOp
                 }
             }
-        } else {
-            Msg::NoOp
+            None => None,
+        };
+
+        let mut result = Vec::new();
+        for file in files {
+          if let Some(converted) = Self::convert(file, loaded_tile.clone(), strip_metadata, output_format, fill_mode, fill_color) {
+              result.push(converted);
+          }
         }
+        Msg::ConvertedFiles(result)
     }
 
-    fn convert(file: FileDetails, tile: DynamicImage) -> gloo_file::File {
+    /// Tries the embedded native server's `/convert` endpoint, which runs the
+    /// same pipeline off the WASM main thread with full CPU parallelism. Returns
+    /// `None` (rather than erroring) when no such backend is reachable, e.g. a
+    /// pure-web deployment, so the caller can fall back to the in-browser path.
+    ///
+    /// The backend's reachability is cached after the first attempt (WASM is
+    /// single-threaded, so a thread-local is enough) so a pure-web deployment
+    /// doesn't re-upload the whole batch to a nonexistent endpoint on every click.
+    async fn convert_via_backend(files: &[FileDetails], tile: Option<&FileDetails>, strip_metadata: bool, output_format: OutputFormat, fill_mode: FillMode, fill_color: [u8; 3]) -> Option<Msg> {
+        thread_local! {
+            static BACKEND_AVAILABLE: std::cell::Cell<Option<bool>> = std::cell::Cell::new(None);
+        }
+
+        if BACKEND_AVAILABLE.with(|flag| flag.get()) == Some(false) {
+            return None;
+        }
+
+        let request = BackendConvertRequest {
+            tile: tile.map(|tile| general_purpose::STANDARD.encode(&tile.data)),
+            strip_metadata,
+            format: output_format.select_value().to_string(),
+            quality: match output_format {
+                OutputFormat::Jpeg(quality) => Some(quality),
+                _ => None,
+            },
+            fill_mode: fill_mode.select_value().to_string(),
+            fill_color,
+            files: files.iter().map(|file| BackendFileRequest {
+                name: file.name.clone(),
+                data: general_purpose::STANDARD.encode(&file.data),
+            }).collect(),
+        };
+
+        let response = match gloo_net::http::Request::post("/convert").json(&request).ok()?.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                error!(format!("No native backend reachable: {}", e));
+                BACKEND_AVAILABLE.with(|flag| flag.set(Some(false)));
+                return None;
+            }
+        };
+
+        if !response.ok() {
+            error!(format!("Native backend returned {}", response.status()));
+            // A static host with no /convert route answers with a plain HTTP
+            // response rather than a transport error, so treat "not found" /
+            // "method not allowed" as "no backend" too, not just connection failure.
+            if response.status() == 404 || response.status() == 405 {
+                BACKEND_AVAILABLE.with(|flag| flag.set(Some(false)));
+            }
+            return None;
+        }
+        BACKEND_AVAILABLE.with(|flag| flag.set(Some(true)));
+
+        let converted: Vec<BackendConvertedFile> = response.json().await.ok()?;
+        let files = converted.into_iter().filter_map(|file| {
+            let data = general_purpose::STANDARD.decode(&file.data).ok()?;
+            Some(gloo_file::File::new_with_options::<&[u8]>(&file.name, data.as_bytes(), Some(&file.mime_type), None))
+        }).collect();
+
+        Some(Msg::ConvertedFiles(files))
+    }
+
+    fn convert(file: FileDetails, tile: Option<DynamicImage>, strip_metadata: bool, output_format: OutputFormat, fill_mode: FillMode, fill_color: [u8; 3]) -> Option<gloo_file::File> {
         info!(format!("Loading file: {}", file.name));
-        let old = image::load_from_memory_with_format(&file.data, ImageFormat::from_mime_type(&file.file_type).unwrap()).unwrap();
+
+        let format = match image::guess_format(&file.data) {
+            Ok(format) => format,
+            Err(_) => match ImageFormat::from_mime_type(&file.file_type) {
+                Some(format) => format,
+                None => {
+                    error!(format!("Could not determine the format of {}, skipping", file.name));
+                    return None;
+                }
+            },
+        };
+
+        if format == ImageFormat::Gif && output_format == OutputFormat::Gif {
+            return Self::convert_gif(file, tile, fill_mode, fill_color);
+        }
+
+        let old = match image::load_from_memory_with_format(&file.data, format) {
+            Ok(old) => old,
+            Err(e) => {
+                error!(format!("Error decoding {}: {}", file.name, e));
+                return None;
+            }
+        };
+
+        let old = if strip_metadata {
+            info!("Stripping metadata from image");
+            image::DynamicImage::ImageRgba8(old.to_rgba8())
+        } else {
+            old
+        };
+
         let (width, height) = old.dimensions();
         info!(format!("{}x{}", width, height));
         let max = width.max(height);
 
-        info!("Creating new image");
-        let new = image::RgbaImage::new(max, max);
-        let mut new = image::DynamicImage::ImageRgba8(new);
-
-        info!("Tiling image background");
-        image::imageops::tile(&mut new, &tile);
+        info!("Creating background");
+        let mut new = build_background(fill_mode, fill_color, tile.as_ref(), max, &old);
 
         info!("Overlaying old image");
         image::imageops::overlay(&mut new, &old, ((max - width) / 2) as i64, ((max - height) / 2) as i64);
 
         info!("Saving new image to buffer");
-        let mut new_buffer = Cursor::new(vec![]);
-        new.write_to(&mut new_buffer, image::ImageOutputFormat::Png).unwrap();
-        
+        let (new_buffer, output_format) = match pipeline::encode(new, output_format) {
+            Ok(result) => result,
+            Err(e) => {
+                error!(format!("Error encoding {}: {}", file.name, e));
+                return None;
+            }
+        };
+
         info!("Pushing new file to result");
-        gloo_file::File::new_with_options::<&[u8]>(&file.name, new_buffer.into_inner().as_bytes(), Some(&file.file_type), None)
+        let name = with_output_extension(&file.name, output_format);
+        Some(gloo_file::File::new_with_options::<&[u8]>(&name, new_buffer.as_bytes(), Some(output_format.mime_type()), None))
+    }
+
+    /// Squares up every frame of an animated GIF instead of collapsing it to its
+    /// first frame, using the largest frame dimension across the animation so the
+    /// canvas size stays stable throughout. `strip_metadata` has no effect here:
+    /// re-encoding through `GifEncoder` only ever copies frame pixel data, so
+    /// GIF-level metadata (comment/application extensions) is already dropped.
+    fn convert_gif(file: FileDetails, tile: Option<DynamicImage>, fill_mode: FillMode, fill_color: [u8; 3]) -> Option<gloo_file::File> {
+        info!(format!("Loading animated GIF: {}", file.name));
+        let decoder = match GifDecoder::new(Cursor::new(&file.data)) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                error!(format!("Error decoding GIF {}: {}", file.name, e));
+                return None;
+            }
+        };
+
+        let frames = match decoder.collect_frames() {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!(format!("Error decoding GIF frames for {}: {}", file.name, e));
+                return None;
+            }
+        };
+
+        if frames.is_empty() {
+            error!(format!("{} has no frames", file.name));
+            return None;
+        }
+
+        let max = frames.iter()
+            .map(|frame| {
+                let (width, height) = frame.buffer().dimensions();
+                width.max(height)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut new_buffer = Cursor::new(vec![]);
+        {
+            let mut encoder = GifEncoder::new(&mut new_buffer);
+            if let Err(e) = encoder.set_repeat(Repeat::Infinite) {
+                error!(format!("Error configuring GIF repeat for {}: {}", file.name, e));
+                return None;
+            }
+
+            for frame in frames {
+                let delay = frame.delay();
+                let old = DynamicImage::ImageRgba8(frame.into_buffer());
+                let (width, height) = old.dimensions();
+
+                let mut canvas = build_background(fill_mode, fill_color, tile.as_ref(), max, &old);
+                image::imageops::overlay(&mut canvas, &old, ((max - width) / 2) as i64, ((max - height) / 2) as i64);
+
+                let squared_frame = Frame::from_parts(canvas.to_rgba8(), 0, 0, delay);
+                if let Err(e) = encoder.encode_frame(squared_frame) {
+                    error!(format!("Error encoding GIF frame for {}: {}", file.name, e));
+                    return None;
+                }
+            }
+        }
+
+        info!("Pushing new animated GIF to result");
+        let name = with_output_extension(&file.name, OutputFormat::Gif);
+        Some(gloo_file::File::new_with_options::<&[u8]>(&name, new_buffer.into_inner().as_bytes(), Some("image/gif"), None))
+    }
+
+    fn parse_hex_color(hex: &str) -> [u8; 3] {
+        let hex = hex.trim_start_matches('#');
+        let r = u8::from_str_radix(hex.get(0..2).unwrap_or("ff"), 16).unwrap_or(255);
+        let g = u8::from_str_radix(hex.get(2..4).unwrap_or("ff"), 16).unwrap_or(255);
+        let b = u8::from_str_radix(hex.get(4..6).unwrap_or("ff"), 16).unwrap_or(255);
+        [r, g, b]
+    }
+
+    fn download_all(files: &[FileDetails]) -> Result<(), String> {
+        info!("Building zip archive");
+        let mut buffer = Cursor::new(Vec::new());
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let mut zip = zip::ZipWriter::new(&mut buffer);
+        for file in files {
+            zip.start_file(&file.name, options).map_err(|e| e.to_string())?;
+            zip.write_all(&file.data).map_err(|e| e.to_string())?;
+        }
+        zip.finish().map_err(|e| e.to_string())?;
+        drop(zip);
+
+        Self::trigger_download("processed_images.zip", "application/zip", buffer.get_ref())
+    }
+
+    fn trigger_download(name: &str, mime_type: &str, data: &[u8]) -> Result<(), String> {
+        let array = js_sys::Uint8Array::from(data);
+        let sequence = js_sys::Array::of1(&array.into());
+
+        let mut options = BlobPropertyBag::new();
+        options.type_(mime_type);
+        let blob = Blob::new_with_u8_array_sequence_and_options(&sequence, &options)
+            .map_err(|_| "failed to create blob".to_string())?;
+
+        let url = Url::create_object_url_with_blob(&blob).map_err(|_| "failed to create object URL".to_string())?;
+
+        let window = web_sys::window().ok_or("no window available")?;
+        let document = window.document().ok_or("no document available")?;
+        let anchor = document.create_element("a").map_err(|_| "failed to create anchor".to_string())?;
+        let anchor: HtmlAnchorElement = anchor.dyn_into().map_err(|_| "anchor is not an HtmlAnchorElement".to_string())?;
+        anchor.set_href(&url);
+        anchor.set_download(name);
+        anchor.click();
+
+        // Revoking immediately after click() can race the browser's download
+        // kickoff and invalidate the blob before it's read, so defer it instead.
+        gloo_timers::callback::Timeout::new(1_000, move || {
+            if Url::revoke_object_url(&url).is_err() {
+                error!("Failed to revoke object URL");
+            }
+        }).forget();
+        Ok(())
     }
 
     fn button_text(props: Props) -> Html {
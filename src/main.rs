@@ -1,15 +1,51 @@
 #![windows_subsystem = "windows"]
 
-use std::{borrow::Cow, convert::Infallible, thread};
+use std::{borrow::Cow, convert::Infallible, io::Cursor};
 
-use hyper::{Request, Body, Response, StatusCode, Server, service::{service_fn, make_service_fn}};
+use base64::{Engine as _, engine::general_purpose};
+use hyper::{Request, Body, Response, StatusCode, Server, Method, service::{service_fn, make_service_fn}};
+use image::{AnimationDecoder, DynamicImage, Frame, GenericImageView, ImageFormat};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use rayon::prelude::*;
 use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
+
+mod pipeline;
+use pipeline::{build_background, with_output_extension, FillMode, OutputFormat};
 
 #[derive(RustEmbed)]
 #[folder = "static"]
 struct Asset;
 
+#[derive(Deserialize)]
+struct ConvertFileRequest {
+    name: String,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct ConvertRequest {
+    tile: Option<String>,
+    files: Vec<ConvertFileRequest>,
+    strip_metadata: bool,
+    format: String,
+    quality: Option<u8>,
+    fill_mode: String,
+    fill_color: [u8; 3],
+}
+
+#[derive(Serialize)]
+struct ConvertedFile {
+    name: String,
+    mime_type: String,
+    data: String,
+}
+
 async fn request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() == Method::POST && req.uri().path() == "/convert" {
+        return convert(req).await;
+    }
+
     let path = if req.uri().path() == "/" {
         "index.html"
     } else {
@@ -35,6 +71,155 @@ async fn request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
     }
 }
 
+/// Runs the tile/overlay pipeline natively, off the WASM main thread, so large
+/// batches don't freeze the UI. Mirrors `app::App::convert`.
+async fn convert(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(convert_error(StatusCode::BAD_REQUEST, format!("failed to read request body: {}", e))),
+    };
+
+    let request: ConvertRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(convert_error(StatusCode::BAD_REQUEST, format!("invalid request body: {}", e))),
+    };
+
+    match tokio::task::spawn_blocking(move || convert_all(request)).await {
+        Ok(Ok(files)) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&files).unwrap()))
+            .unwrap()),
+        Ok(Err(e)) => Ok(convert_error(StatusCode::BAD_REQUEST, e)),
+        Err(e) => Ok(convert_error(StatusCode::INTERNAL_SERVER_ERROR, format!("conversion task panicked: {}", e))),
+    }
+}
+
+fn convert_error(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(format!("{{\"error\":{:?}}}", message)))
+        .unwrap()
+}
+
+fn convert_all(request: ConvertRequest) -> Result<Vec<ConvertedFile>, String> {
+    let tile = match request.tile {
+        Some(tile) => {
+            let tile_bytes = general_purpose::STANDARD.decode(&tile).map_err(|e| format!("invalid tile data: {}", e))?;
+            let tile = image::load_from_memory(&tile_bytes).map_err(|e| format!("failed to decode tile: {}", e))?;
+            let tile = tile.resize(256, 256, image::imageops::FilterType::Nearest);
+            Some(DynamicImage::ImageRgba8(tile.to_rgba8()))
+        }
+        None => None,
+    };
+
+    let output_format = OutputFormat::parse(&request.format, request.quality)?;
+    let fill_mode = FillMode::parse(&request.fill_mode)?;
+    let fill_color = request.fill_color;
+    let strip_metadata = request.strip_metadata;
+
+    if fill_mode == FillMode::Tile && tile.is_none() {
+        return Err("tile fill mode requires a tile image".to_string());
+    }
+
+    // Rayon's global pool bounds concurrency to the available CPUs, so a large
+    // batch can't spawn one OS thread per file.
+    let converted: Vec<ConvertedFile> = request.files
+        .into_par_iter()
+        .filter_map(|file| {
+            let tile = tile.clone();
+            match convert_one(file, tile, strip_metadata, output_format, fill_mode, fill_color) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("convert: {}", e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    Ok(converted)
+}
+
+fn convert_one(file: ConvertFileRequest, tile: Option<DynamicImage>, strip_metadata: bool, output_format: OutputFormat, fill_mode: FillMode, fill_color: [u8; 3]) -> Result<ConvertedFile, String> {
+    let data = general_purpose::STANDARD.decode(&file.data).map_err(|e| format!("invalid data for {}: {}", file.name, e))?;
+
+    let format = image::guess_format(&data).map_err(|e| format!("could not determine format for {}: {}", file.name, e))?;
+
+    if format == ImageFormat::Gif && output_format == OutputFormat::Gif {
+        return convert_gif(file, data, tile, fill_mode, fill_color);
+    }
+
+    let old = image::load_from_memory_with_format(&data, format).map_err(|e| format!("failed to decode {}: {}", file.name, e))?;
+
+    let old = if strip_metadata {
+        DynamicImage::ImageRgba8(old.to_rgba8())
+    } else {
+        old
+    };
+
+    let (width, height) = old.dimensions();
+    let max = width.max(height);
+
+    let mut new = build_background(fill_mode, fill_color, tile.as_ref(), max, &old);
+    image::imageops::overlay(&mut new, &old, ((max - width) / 2) as i64, ((max - height) / 2) as i64);
+
+    let (bytes, output_format) = pipeline::encode(new, output_format).map_err(|e| format!("failed to encode {}: {}", file.name, e))?;
+
+    Ok(ConvertedFile {
+        name: with_output_extension(&file.name, output_format),
+        mime_type: output_format.mime_type().to_string(),
+        data: general_purpose::STANDARD.encode(bytes),
+    })
+}
+
+/// Squares up every frame of an animated GIF instead of collapsing it to its
+/// first frame, using the largest frame dimension across the animation so the
+/// canvas size stays stable throughout. Mirrors `app::App::convert_gif`.
+/// `strip_metadata` has no effect here: re-encoding through `GifEncoder` only
+/// ever copies frame pixel data, so GIF-level metadata is already dropped.
+fn convert_gif(file: ConvertFileRequest, data: Vec<u8>, tile: Option<DynamicImage>, fill_mode: FillMode, fill_color: [u8; 3]) -> Result<ConvertedFile, String> {
+    let decoder = GifDecoder::new(Cursor::new(data)).map_err(|e| format!("failed to decode GIF {}: {}", file.name, e))?;
+    let frames = decoder.collect_frames().map_err(|e| format!("failed to decode GIF frames for {}: {}", file.name, e))?;
+
+    if frames.is_empty() {
+        return Err(format!("{} has no frames", file.name));
+    }
+
+    let max = frames.iter()
+        .map(|frame| {
+            let (width, height) = frame.buffer().dimensions();
+            width.max(height)
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut buffer = Cursor::new(vec![]);
+    {
+        let mut encoder = GifEncoder::new(&mut buffer);
+        encoder.set_repeat(Repeat::Infinite).map_err(|e| format!("failed to configure GIF repeat for {}: {}", file.name, e))?;
+
+        for frame in frames {
+            let delay = frame.delay();
+            let old = DynamicImage::ImageRgba8(frame.into_buffer());
+            let (width, height) = old.dimensions();
+
+            let mut canvas = build_background(fill_mode, fill_color, tile.as_ref(), max, &old);
+            image::imageops::overlay(&mut canvas, &old, ((max - width) / 2) as i64, ((max - height) / 2) as i64);
+
+            let squared_frame = Frame::from_parts(canvas.to_rgba8(), 0, 0, delay);
+            encoder.encode_frame(squared_frame).map_err(|e| format!("failed to encode GIF frame for {}: {}", file.name, e))?;
+        }
+    }
+
+    Ok(ConvertedFile {
+        name: with_output_extension(&file.name, OutputFormat::Gif),
+        mime_type: "image/gif".to_string(),
+        data: general_purpose::STANDARD.encode(buffer.into_inner()),
+    })
+}
+
 #[tokio::main]
 async fn main() {
     let addr = ([127, 0, 0, 1], 0).into();
@@ -47,11 +232,14 @@ async fn main() {
 
     let port = server.local_addr().port();
 
-    thread::spawn(move || { async {
+    // #[tokio::main] defaults to a multi-threaded runtime, so this is actually
+    // driven to completion by another worker thread while this thread blocks
+    // on web_view::run() below.
+    tokio::spawn(async move {
         if let Err(e) = server.await {
             eprintln!("server error: {}", e);
         }
-    }});
+    });
 
     web_view::builder()
         .title("Square Images")
@@ -63,4 +251,4 @@ async fn main() {
         .invoke_handler(|_webview, _arg| Ok(()))
         .run()
         .unwrap();
-}
\ No newline at end of file
+}
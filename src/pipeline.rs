@@ -0,0 +1,165 @@
+//! The image-squaring pipeline shared by the WASM frontend (`app.rs`) and the
+//! native backend (`main.rs`): output format/fill mode selection and the
+//! background-compositing logic they both drive the same `image` calls through.
+
+use std::io::Cursor;
+
+use image::DynamicImage;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg(u8),
+    WebP,
+    Gif,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Png
+    }
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str, quality: Option<u8>) -> Result<Self, String> {
+        match format {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" => Ok(OutputFormat::Jpeg(quality.unwrap_or(85))),
+            "webp" => Ok(OutputFormat::WebP),
+            "gif" => Ok(OutputFormat::Gif),
+            other => Err(format!("unsupported output format: {}", other)),
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg(_) => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Gif => "image/gif",
+        }
+    }
+
+    pub fn select_value(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg(_) => "jpeg",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Gif => "gif",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg(_) => "jpg",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Gif => "gif",
+        }
+    }
+}
+
+impl From<OutputFormat> for image::ImageOutputFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Png => image::ImageOutputFormat::Png,
+            OutputFormat::Jpeg(quality) => image::ImageOutputFormat::Jpeg(quality),
+            OutputFormat::WebP => image::ImageOutputFormat::WebP,
+            OutputFormat::Gif => image::ImageOutputFormat::Gif,
+        }
+    }
+}
+
+/// Replaces `name`'s extension (if any) with the one matching `format`, so a
+/// converted file's name reflects the format it was actually re-encoded to.
+pub fn with_output_extension(name: &str, format: OutputFormat) -> String {
+    let stem = match name.rfind('.') {
+        Some(dot) => &name[..dot],
+        None => name,
+    };
+    format!("{}.{}", stem, format.extension())
+}
+
+/// Encodes `image` as `format`, converting to the color type each encoder
+/// requires (JPEG rejects RGBA) first. WebP encoder support varies across
+/// `image` versions, so a WebP encode failure degrades to PNG instead of
+/// dropping the file outright; the caller gets back whichever format was
+/// actually written so it can name/MIME-type the result correctly.
+pub fn encode(image: DynamicImage, format: OutputFormat) -> Result<(Vec<u8>, OutputFormat), String> {
+    let image = match format {
+        OutputFormat::Jpeg(_) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image,
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    if let Err(e) = image.write_to(&mut buffer, format.into()) {
+        if format != OutputFormat::WebP {
+            return Err(e.to_string());
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_to(&mut buffer, OutputFormat::Png.into()).map_err(|e| e.to_string())?;
+        return Ok((buffer.into_inner(), OutputFormat::Png));
+    }
+
+    Ok((buffer.into_inner(), format))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FillMode {
+    Tile,
+    Solid,
+    Blur,
+}
+
+impl Default for FillMode {
+    fn default() -> Self {
+        FillMode::Tile
+    }
+}
+
+impl FillMode {
+    pub fn parse(fill_mode: &str) -> Result<Self, String> {
+        match fill_mode {
+            "tile" => Ok(FillMode::Tile),
+            "solid" => Ok(FillMode::Solid),
+            "blur" => Ok(FillMode::Blur),
+            other => Err(format!("unsupported fill mode: {}", other)),
+        }
+    }
+
+    pub fn select_value(&self) -> &'static str {
+        match self {
+            FillMode::Tile => "tile",
+            FillMode::Solid => "solid",
+            FillMode::Blur => "blur",
+        }
+    }
+}
+
+/// Builds the square background for the given fill mode: the uploaded tile
+/// repeated across the canvas, a flat color, or the source image itself
+/// scaled up to cover the canvas and blurred as a backdrop.
+pub fn build_background(fill_mode: FillMode, fill_color: [u8; 3], tile: Option<&DynamicImage>, max: u32, source: &DynamicImage) -> DynamicImage {
+    match fill_mode {
+        FillMode::Tile => {
+            let mut canvas = DynamicImage::ImageRgba8(image::RgbaImage::new(max, max));
+            if let Some(tile) = tile {
+                image::imageops::tile(&mut canvas, tile);
+            }
+            canvas
+        }
+        FillMode::Solid => {
+            let [r, g, b] = fill_color;
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(max, max, image::Rgba([r, g, b, 255])))
+        }
+        FillMode::Blur => {
+            // A Gaussian blur at full canvas resolution is slow enough to freeze
+            // a caller running on the WASM main thread, so blur a small
+            // downscaled copy and scale the result back up instead.
+            const BLUR_WORKING_SIZE: u32 = 64;
+            let covering = source.resize_to_fill(BLUR_WORKING_SIZE, BLUR_WORKING_SIZE, image::imageops::FilterType::Triangle);
+            let blurred = image::imageops::blur(&covering, BLUR_WORKING_SIZE as f32 / 16.0);
+            DynamicImage::ImageRgba8(blurred).resize_to_fill(max, max, image::imageops::FilterType::Triangle)
+        }
+    }
+}